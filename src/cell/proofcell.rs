@@ -4,16 +4,7 @@
 use std::cell::{ BorrowError, BorrowMutError, Ref, RefCell, RefMut, UnsafeCell };
 use std::marker::PhantomData;
 
-/// A trait specifying that a structure supports immutable borrowing if some proof is provided.
-pub trait ProofBorrow<P, T> {
-    fn borrow<'a>(&'a self, proof: &P) -> &'a T;
-}
-
-
-/// A trait specifying that a structure supports mutable borrowing if some proof is provided.
-pub trait ProofBorrowMut<P, T> {
-    fn borrow_mut<'a>(&'a self, proof: &P) -> &'a mut T;
-}
+pub use crate::proof::{ ProofBorrow, ProofBorrowMut };
 
 pub struct SubCell<T> {
     cell: UnsafeCell<T>,
@@ -29,6 +20,30 @@ impl<T> SubCell<T> {
             owner_key: proof.0,
         }
     }
+
+    /// Replace the value, without materializing a `&mut T`, as `Cell::set`.
+    pub fn set<'a>(&self, proof: &ProofMut<'a>, value: T) {
+        assert_eq!(self.owner_key, proof.0);
+        unsafe { *self.cell.get() = value; }
+    }
+
+    /// Return a copy of the current value, as `Cell::get`.
+    pub fn get<'a>(&self, proof: &ProofMut<'a>) -> T where T: Copy {
+        assert_eq!(self.owner_key, proof.0);
+        unsafe { *self.cell.get() }
+    }
+
+    /// Replace the value and return the previous one, as `Cell::replace`.
+    pub fn replace<'a>(&self, proof: &ProofMut<'a>, value: T) -> T {
+        use std::mem;
+        assert_eq!(self.owner_key, proof.0);
+        unsafe { mem::replace(&mut *self.cell.get(), value) }
+    }
+
+    /// Replace the value with its `Default` and return the previous one, as `Cell::take`.
+    pub fn take<'a>(&self, proof: &ProofMut<'a>) -> T where T: Default {
+        self.replace(proof, T::default())
+    }
 }
 
 impl<'b, T> ProofBorrow<Proof<'b>, T> for SubCell<T> {
@@ -75,20 +90,20 @@ pub type WriteGuard<'a, T> = (ProofMut<'a>, RefMut<'a, T>);
 ///
 /// {
 ///     println!("* Attempt to read in the MainCell.");
-///     let (_, guard) = data.try_borrow().unwrap();
+///     let (_, guard) = data.read().unwrap();
 ///     assert_eq!(guard.len(), 0);
 /// }
 ///
 /// {
 ///     println!("* Attempt to write in the MainCell.");
-///     let (proof, mut guard) = data.try_borrow_mut().unwrap();
+///     let (proof, mut guard) = data.write().unwrap();
 ///     guard.insert(0, SubCell::new(&proof, 42));
 ///     assert_eq!(guard.len(), 1);
 /// }
 ///
 /// {
 ///     println!("* Attempt to read in a SubCell.");
-///     let (proof, guard) = data.try_borrow().unwrap();
+///     let (proof, guard) = data.read().unwrap();
 ///     assert_eq!(guard.len(), 1);
 ///     let cell = guard.get(&0).unwrap();
 ///     assert_eq!(*cell.borrow(&proof), 42);
@@ -96,7 +111,7 @@ pub type WriteGuard<'a, T> = (ProofMut<'a>, RefMut<'a, T>);
 ///
 /// {
 ///     println!("* Attempt to read and write in a SubCell.");
-///     let (proof, guard) = data.try_borrow_mut().unwrap();
+///     let (proof, guard) = data.write().unwrap();
 ///     assert_eq!(guard.len(), 1);
 ///     let cell = guard.get(&0).unwrap();
 ///     assert_eq!(*cell.borrow(&proof), 42);
@@ -107,11 +122,27 @@ pub type WriteGuard<'a, T> = (ProofMut<'a>, RefMut<'a, T>);
 ///
 /// {
 ///     println!("* Check that the SubCell changes are kept.");
-///     let (proof, guard) = data.try_borrow().unwrap();
+///     let (proof, guard) = data.read().unwrap();
 ///     assert_eq!(guard.len(), 1);
 ///     let cell = guard.get(&0).unwrap();
 ///     assert_eq!(*cell.borrow(&proof), 99);
 /// }
+///
+/// {
+///     println!("* Cell-style get/set/replace/take, still gated by the proof.");
+///     let (proof, guard) = data.write().unwrap();
+///     let cell = guard.get(&0).unwrap();
+///
+///     assert_eq!(cell.get(&proof), 99);
+///     cell.set(&proof, 1);
+///     assert_eq!(cell.get(&proof), 1);
+///
+///     assert_eq!(cell.replace(&proof, 2), 1);
+///     assert_eq!(cell.get(&proof), 2);
+///
+///     assert_eq!(cell.take(&proof), 2);
+///     assert_eq!(cell.get(&proof), 0);
+/// }
 /// ```
 pub struct MainCell<T> {
     cell: RefCell<T>,
@@ -127,7 +158,8 @@ impl<T> MainCell<T> {
         }
     }
 
-    pub fn try_borrow(&self) -> Result<ReadGuard<T>, BorrowError> {
+    // As `RwLock.read`. A `RefCell` never blocks, so `read` and `try_read` are identical.
+    pub fn read(&self) -> Result<ReadGuard<T>, BorrowError> {
         let proof = Proof(self.ownership, PhantomData);
         match self.cell.try_borrow() {
             Ok(ok) => Ok((proof, ok)),
@@ -135,12 +167,23 @@ impl<T> MainCell<T> {
         }
     }
 
-    pub fn try_borrow_mut(&self) -> Result<WriteGuard<T>, BorrowMutError> {
+    // As `RwLock.try_read`.
+    pub fn try_read(&self) -> Result<ReadGuard<T>, BorrowError> {
+        self.read()
+    }
+
+    // As `RwLock.write`. A `RefCell` never blocks, so `write` and `try_write` are identical.
+    pub fn write(&self) -> Result<WriteGuard<T>, BorrowMutError> {
         let proof = ProofMut(self.ownership, PhantomData);
         match self.cell.try_borrow_mut() {
             Ok(ok) => Ok((proof, ok)),
             Err(err) => Err(err)
         }
     }
+
+    // As `RwLock.try_write`.
+    pub fn try_write(&self) -> Result<WriteGuard<T>, BorrowMutError> {
+        self.write()
+    }
 }
 
@@ -1,9 +1,36 @@
 //! A crate designed to allow batch-locking/batch-unlocking of groups of locks.
 //!
 //! This crate was initially designed to permit refactoring of code using `RefCell` into `Sync` code.
+//!
+//! `cell` and `sync` are built on the standard library and require the (default-enabled) `std`
+//! feature. With `std` disabled, only the `spin` backend is available and the crate is
+//! `#![no_std]`, for use in embedded and other `no_std` contexts.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+/// Traits shared by the proof-based sublock variants, across `cell`, `sync` and `spin`.
+pub mod proof;
 
-// Locks for single-treaded use.
+// Locks for single-treaded use. Requires the `std` feature.
+#[cfg(feature = "std")]
 pub mod cell;
 
-// Locks for multi-threaded use.
-pub mod sync;
\ No newline at end of file
+// Locks for multi-threaded use. Requires the `std` feature.
+#[cfg(feature = "std")]
+pub mod sync;
+
+// A `no_std` variant built on a spinning lock, for embedded use.
+#[cfg(feature = "spin")]
+pub mod spin;
+
+// Unified, feature-switched aliases over the proof-based sublock machinery: write against
+// `sublock::{MainLock, SubCell, Proof, ProofMut}` once, then compile either as cheap
+// single-threaded `RefCell` code or as `Sync` multi-threaded `RwLock` code by flipping the
+// `concurrent` feature. This is the "refactor `RefCell` code into `Sync` code" use case
+// advertised above. Requires the `std` feature, since both backends are built on it.
+#[cfg(all(feature = "std", not(feature = "concurrent")))]
+pub use cell::proofcell::MainCell as MainLock;
+#[cfg(all(feature = "std", not(feature = "concurrent")))]
+pub use cell::proofcell::{ SubCell, Proof, ProofMut, ReadGuard, WriteGuard };
+
+#[cfg(all(feature = "std", feature = "concurrent"))]
+pub use sync::prooflock::{ MainLock, SubCell, Proof, ProofMut, ReadGuard, WriteGuard };
\ No newline at end of file
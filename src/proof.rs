@@ -0,0 +1,13 @@
+//! Traits shared by the proof-based sublock variants (`cell::proofcell`, `sync::prooflock` and
+//! `spin`): a `SubCell` can be borrowed as soon as its caller can produce a proof that the lock
+//! owning it is currently opened.
+
+/// A trait specifying that a structure supports immutable borrowing if some proof is provided.
+pub trait ProofBorrow<P, T> {
+    fn borrow<'a>(&'a self, proof: &P) -> &'a T;
+}
+
+/// A trait specifying that a structure supports mutable borrowing if some proof is provided.
+pub trait ProofBorrowMut<P, T> {
+    fn borrow_mut<'a>(&'a self, proof: &P) -> &'a mut T;
+}
@@ -0,0 +1,197 @@
+//! A `no_std` variant of `sync::prooflock`, built on a simple spinning lock (as in the `spin`
+//! crate) instead of OS primitives, so the batch-sublock pattern can be used in embedded and
+//! other `no_std` contexts. Only `core` is used, no `alloc`.
+//!
+//! Enabled with the `spin` feature.
+
+use core::cell::UnsafeCell;
+use core::hint;
+use core::marker::PhantomData;
+use core::ops::{ Deref, DerefMut };
+use core::sync::atomic::{ AtomicBool, Ordering };
+
+pub use crate::proof::{ ProofBorrow, ProofBorrowMut };
+
+pub struct SubCell<T> {
+    cell: UnsafeCell<T>,
+
+    // The owner has type MainLock<_> and has a unique key equal to `owner_key`.
+    owner_key: usize,
+}
+
+impl<T> SubCell<T> {
+    pub fn new<'a>(proof: &ProofMut<'a>, value: T) -> Self {
+        SubCell {
+            cell: UnsafeCell::new(value),
+            owner_key: proof.0,
+        }
+    }
+}
+
+impl<'b, T> ProofBorrow<Proof<'b>, T> for SubCell<T> {
+    fn borrow<'a>(&'a self, proof: &Proof<'b>) -> &'a T {
+        assert_eq!(self.owner_key, proof.0);
+        unsafe { &*self.cell.get() }
+    }
+}
+
+impl<'b, T> ProofBorrow<ProofMut<'b>, T> for SubCell<T> {
+    fn borrow<'a>(&'a self, proof: &ProofMut<'b>) -> &'a T {
+        assert_eq!(self.owner_key, proof.0);
+        unsafe { &*self.cell.get() }
+    }
+}
+
+impl<'b, T> ProofBorrowMut<ProofMut<'b>, T> for SubCell<T> {
+    fn borrow_mut<'a>(&'a self, proof: &ProofMut<'b>) -> &'a mut T {
+        assert_eq!(self.owner_key, proof.0);
+        unsafe { &mut *self.cell.get() }
+    }
+}
+
+/// With respect to Send and Sync, SubCell behaves as a RwLock.
+unsafe impl<T> Send for SubCell<T> where T: Send + Sync { }
+
+/// With respect to Send and Sync, SubCell behaves as a RwLock.
+unsafe impl<T> Sync for SubCell<T> where T: Send + Sync { }
+
+/// A proof that the MainLock is currently opened.
+/// Its lifetime is limited by that of the ReadGuard that provided it.
+pub struct Proof<'a>(usize, PhantomData<&'a()>);
+
+/// A proof that the MainLock is currently opened mutably.
+/// Its lifetime is limited by that of the WriteGuard that provided it.
+pub struct ProofMut<'a>(usize, PhantomData<&'a()>);
+
+pub type ReadGuard<'a, T> = (Proof<'a>, SpinReadGuard<'a, T>);
+pub type WriteGuard<'a, T> = (ProofMut<'a>, SpinWriteGuard<'a, T>);
+
+/// A variant of `RwLock` with sublocks that can be opened at no cost by providing a proof
+/// that the main lock is opened, built on a spinning lock instead of an OS primitive.
+///
+/// Unlike `sync::prooflock::MainLock`, `read` and `write` both spin on the same lock bit: this
+/// keeps the implementation to a single `AtomicBool`, at the cost of serializing readers the
+/// way a mutex would. This matches the batch-sublock use case, where most of the cost is paid
+/// once per batch rather than once per reader.
+///
+/// ```
+/// use sublock::spin::*;
+///
+/// type State = [Option<SubCell<usize>>; 1];
+/// let data : MainLock<State> = MainLock::new([None]);
+///
+/// {
+///     println!("* Attempt to write in the MainLock.");
+///     let (proof, mut guard) = data.write();
+///     guard[0] = Some(SubCell::new(&proof, 42));
+/// }
+///
+/// {
+///     println!("* Attempt to read in a SubCell.");
+///     let (proof, guard) = data.read();
+///     let cell = guard[0].as_ref().unwrap();
+///     assert_eq!(*cell.borrow(&proof), 42);
+/// }
+///
+/// {
+///     println!("* Attempt to read and write in a SubCell.");
+///     let (proof, guard) = data.write();
+///     let cell = guard[0].as_ref().unwrap();
+///     assert_eq!(*cell.borrow(&proof), 42);
+///
+///     *cell.borrow_mut(&proof) = 99;
+///     assert_eq!(*cell.borrow(&proof), 99);
+/// }
+/// ```
+pub struct MainLock<T> {
+    locked: AtomicBool,
+    cell: UnsafeCell<T>,
+    ownership: usize,
+}
+
+unsafe impl<T> Sync for MainLock<T> where T: Send { }
+
+impl<T> MainLock<T> {
+    pub fn new(value: T) -> Self {
+        let ownership: usize = &value as *const T as usize;
+        MainLock {
+            locked: AtomicBool::new(false),
+            cell: UnsafeCell::new(value),
+            ownership: ownership,
+        }
+    }
+
+    fn spin_lock(&self) {
+        while self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            hint::spin_loop();
+        }
+    }
+
+    // As `RwLock.read`.
+    pub fn read(&self) -> ReadGuard<T> {
+        self.spin_lock();
+        (Proof(self.ownership, PhantomData), SpinReadGuard { lock: self })
+    }
+
+    // As `RwLock.try_read`.
+    pub fn try_read(&self) -> Option<ReadGuard<T>> {
+        match self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => Some((Proof(self.ownership, PhantomData), SpinReadGuard { lock: self })),
+            Err(_) => None,
+        }
+    }
+
+    // As `RwLock.write`.
+    pub fn write(&self) -> WriteGuard<T> {
+        self.spin_lock();
+        (ProofMut(self.ownership, PhantomData), SpinWriteGuard { lock: self })
+    }
+
+    // As `RwLock.try_write`.
+    pub fn try_write(&self) -> Option<WriteGuard<T>> {
+        match self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => Some((ProofMut(self.ownership, PhantomData), SpinWriteGuard { lock: self })),
+            Err(_) => None,
+        }
+    }
+}
+
+pub struct SpinReadGuard<'a, T> where T: 'a {
+    lock: &'a MainLock<T>,
+}
+
+impl<'a, T> Deref for SpinReadGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.cell.get() }
+    }
+}
+
+impl<'a, T> Drop for SpinReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
+
+pub struct SpinWriteGuard<'a, T> where T: 'a {
+    lock: &'a MainLock<T>,
+}
+
+impl<'a, T> Deref for SpinWriteGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.cell.get() }
+    }
+}
+
+impl<'a, T> DerefMut for SpinWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.cell.get() }
+    }
+}
+
+impl<'a, T> Drop for SpinWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
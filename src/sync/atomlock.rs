@@ -2,11 +2,23 @@
 //! opened for reading, opened for writing iff the main lock is currently opened for writing.
 
 use std::cell::{ UnsafeCell };
+use std::error::Error;
+use std::fmt;
 use std::ops::{ Deref, DerefMut };
-use std::sync::atomic::{ AtomicBool, Ordering };
-use std::sync::{ Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockResult };
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use std::sync::Arc;
+
+#[cfg(not(feature = "parking_lot"))]
+use std::sync::{ PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockResult };
+#[cfg(not(feature = "parking_lot"))]
 pub use std::sync::LockResult;
 
+#[cfg(feature = "parking_lot")]
+use parking_lot::{ RwLock, RwLockReadGuard, RwLockUpgradableReadGuard, RwLockWriteGuard };
+
+/// The value of `SubCell::borrowed` that marks the cell as exclusively (mutably) borrowed.
+const EXCLUSIVE: usize = usize::MAX;
+
 pub struct Liveness {
     /// `true` as long as the `MainLock is acquired, `false` after that.
     is_alive: AtomicBool,
@@ -15,10 +27,27 @@ pub struct Liveness {
     is_mut: AtomicBool
 }
 
+/// An attempt to borrow a `SubCell` that conflicted with a borrow already in progress.
+#[derive(Debug)]
+pub struct InvalidBorrow;
+
+impl fmt::Display for InvalidBorrow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "already borrowed")
+    }
+}
+
+impl Error for InvalidBorrow {
+}
+
 pub struct SubCell<T> {
     cell: UnsafeCell<T>,
 
     liveness: Arc<Liveness>,
+
+    /// `0` if the cell is free, `N > 0` if there are `N` active shared borrows, `EXCLUSIVE` if
+    /// there is one active exclusive borrow.
+    borrowed: AtomicUsize,
 }
 
 impl<T> SubCell<T> {
@@ -26,19 +55,105 @@ impl<T> SubCell<T> {
         SubCell {
             cell: UnsafeCell::new(value),
             liveness: liveness.clone(),
+            borrowed: AtomicUsize::new(0),
         }
     }
-    pub fn borrow(&self) -> &T {
+
+    pub fn borrow(&self) -> SubRef<T> {
+        self.try_borrow().expect("Attempting to borrow a SubCell that is already mutably borrowed")
+    }
+
+    pub fn borrow_mut(&self) -> SubRefMut<T> {
+        self.try_borrow_mut().expect("Attempting to borrow_mut a SubCell that is already borrowed")
+    }
+
+    pub fn try_borrow(&self) -> Result<SubRef<T>, InvalidBorrow> {
         assert!(self.liveness.is_alive.load(Ordering::Relaxed), "Attempting to borrow after the MainLock was released");
-        unsafe { &*self.cell.get() }
+        loop {
+            let current = self.borrowed.load(Ordering::Relaxed);
+            if current == EXCLUSIVE {
+                return Err(InvalidBorrow);
+            }
+            if self.borrowed.compare_exchange(current, current + 1, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+                return Ok(SubRef { cell: self });
+            }
+        }
     }
 
-    pub fn borrow_mut(&self) -> &mut T {
+    pub fn try_borrow_mut(&self) -> Result<SubRefMut<T>, InvalidBorrow> {
         assert!(self.liveness.is_alive.load(Ordering::Relaxed), "Attempting to borrow_mut after the MainLock was released.");
         assert!(self.liveness.is_mut.load(Ordering::Relaxed), "Attempting to borrow_mut but the MainLock was acquired immutably.");
-        unsafe { &mut *self.cell.get() }
+        match self.borrowed.compare_exchange(0, EXCLUSIVE, Ordering::Acquire, Ordering::Relaxed) {
+            Ok(_) => Ok(SubRefMut { cell: self }),
+            Err(_) => Err(InvalidBorrow),
+        }
+    }
+
+    /// Return a copy of the current value, as `Cell::get`. Still goes through `borrow` (and
+    /// hence the per-cell borrow counter), since a concurrent `borrow_mut` elsewhere would
+    /// otherwise make this alias a live `&mut T`.
+    pub fn get(&self) -> T where T: Copy {
+        *self.borrow()
+    }
+
+    /// Replace the value, as `Cell::set`. Still goes through `borrow_mut` (and hence the
+    /// per-cell borrow counter), since a concurrent borrow elsewhere would otherwise alias it.
+    pub fn set(&self, value: T) {
+        *self.borrow_mut() = value;
+    }
+
+    /// Replace the value and return the previous one, as `Cell::replace`.
+    pub fn replace(&self, value: T) -> T {
+        use std::mem;
+        mem::replace(&mut *self.borrow_mut(), value)
+    }
+
+    /// Replace the value with its `Default` and return the previous one, as `Cell::take`.
+    pub fn take(&self) -> T where T: Default {
+        self.replace(T::default())
+    }
+}
+
+/// An RAII guard for a shared borrow of a `SubCell`, returned by `SubCell::borrow`.
+pub struct SubRef<'a, T> where T: 'a {
+    cell: &'a SubCell<T>,
+}
+
+impl<'a, T> Deref for SubRef<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.cell.cell.get() }
+    }
+}
+
+impl<'a, T> Drop for SubRef<'a, T> {
+    fn drop(&mut self) {
+        self.cell.borrowed.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// An RAII guard for an exclusive borrow of a `SubCell`, returned by `SubCell::borrow_mut`.
+pub struct SubRefMut<'a, T> where T: 'a {
+    cell: &'a SubCell<T>,
+}
+
+impl<'a, T> Deref for SubRefMut<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.cell.cell.get() }
     }
+}
+
+impl<'a, T> DerefMut for SubRefMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.cell.cell.get() }
+    }
+}
 
+impl<'a, T> Drop for SubRefMut<'a, T> {
+    fn drop(&mut self) {
+        self.cell.borrowed.store(0, Ordering::Release);
+    }
 }
 
 /// With respect to Send and Sync, SubCell behaves as a RwLock.
@@ -68,6 +183,14 @@ unsafe impl<T> Sync for SubCell<T> where T: Send + Sync {
 ///   }
 /// }
 ///
+/// // `read()`/`write()` return `LockResult`/`TryLockResult` with the default (`std`) backend,
+/// // since a `RwLock` can be poisoned, but plain guards with `parking_lot` (which never
+/// // poisons). This local helper keeps the rest of the example the same under both features.
+/// #[cfg(not(feature = "parking_lot"))]
+/// fn unwrap<T, E: std::fmt::Debug>(result: Result<T, E>) -> T { result.unwrap() }
+/// #[cfg(feature = "parking_lot")]
+/// fn unwrap<T>(value: T) -> T { value }
+///
 /// let lock = MainLock::new(|liveness| State {
 ///   live: liveness.clone(),
 ///   data: HashMap::new()
@@ -75,20 +198,20 @@ unsafe impl<T> Sync for SubCell<T> where T: Send + Sync {
 ///
 /// {
 ///     println!("* Attempt to read in the MainLock.");
-///     let guard = lock.read().unwrap();
+///     let guard = unwrap(lock.read());
 ///     assert_eq!(guard.data.len(), 0);
 /// }
 ///
 /// {
 ///     println!("* Attempt to write in the MainLock.");
-///     let mut guard = lock.write().unwrap();
+///     let mut guard = unwrap(lock.write());
 ///     guard.insert(0, 42);
 ///     assert_eq!(guard.data.len(), 1);
 /// }
 ///
 /// {
 ///     println!("* Attempt to read in a SubCell in `read()`.");
-///     let guard = lock.read().unwrap();
+///     let guard = unwrap(lock.read());
 ///     assert_eq!(guard.data.len(), 1);
 ///     let cell = guard.data.get(&0).unwrap();
 ///     assert_eq!(*cell.borrow(), 42);
@@ -96,7 +219,7 @@ unsafe impl<T> Sync for SubCell<T> where T: Send + Sync {
 ///
 /// {
 ///     println!("* Attempt to read and write in a SubCell in `write()`.");
-///     let guard = lock.write().unwrap();
+///     let guard = unwrap(lock.write());
 ///     assert_eq!(guard.data.len(), 1);
 ///     let cell = guard.data.get(&0).unwrap();
 ///     assert_eq!(*cell.borrow(), 42);
@@ -107,11 +230,79 @@ unsafe impl<T> Sync for SubCell<T> where T: Send + Sync {
 ///
 /// {
 ///     println!("* Check that the SubCell changes are kept.");
-///     let guard = lock.read().unwrap();
+///     let guard = unwrap(lock.read());
 ///     assert_eq!(guard.data.len(), 1);
 ///     let cell = guard.data.get(&0).unwrap();
 ///     assert_eq!(*cell.borrow(), 99);
 /// }
+///
+/// {
+///     println!("* Overlapping borrows of the same SubCell are detected at runtime.");
+///     let guard = unwrap(lock.write());
+///     let cell = guard.data.get(&0).unwrap();
+///
+///     let first = cell.borrow();
+///     assert!(cell.try_borrow().is_ok(), "shared borrows can overlap");
+///     assert!(cell.try_borrow_mut().is_err(), "but not with a mutable one");
+///     drop(first);
+///
+///     let _exclusive = cell.borrow_mut();
+///     assert!(cell.try_borrow().is_err(), "a mutable borrow excludes any other borrow");
+///     assert!(cell.try_borrow_mut().is_err(), "including another mutable borrow");
+/// }
+///
+/// {
+///     println!("* Cell-style get/set/replace/take, runtime-checked instead of proof-gated.");
+///     let guard = unwrap(lock.write());
+///     let cell = guard.data.get(&0).unwrap();
+///
+///     assert_eq!(cell.get(), 99);
+///     cell.set(1);
+///     assert_eq!(cell.get(), 1);
+///
+///     assert_eq!(cell.replace(2), 1);
+///     assert_eq!(cell.get(), 2);
+///
+///     assert_eq!(cell.take(), 2);
+///     assert_eq!(cell.get(), 0);
+/// }
+///
+/// {
+///     println!("* Project a guard down to a sub-field with map/filter_map.");
+///     let numbers = MainLock::new(|_| vec![1, 2, 3]);
+///
+///     let guard = unwrap(numbers.read());
+///     let mapped = guard.map(|v| &v[1]);
+///     assert_eq!(*mapped, 2);
+/// }
+///
+/// {
+///     println!("* filter_map succeeds when the projection exists.");
+///     let numbers = MainLock::new(|_| vec![1, 2, 3]);
+///
+///     let guard = unwrap(numbers.read());
+///     let found = guard.filter_map(|v| v.get(0));
+///     assert_eq!(*found.unwrap(), 1);
+/// }
+///
+/// {
+///     println!("* filter_map returns the original guard when the projection fails.");
+///     let numbers = MainLock::new(|_| vec![1, 2, 3]);
+///
+///     let guard = unwrap(numbers.read());
+///     let missing = guard.filter_map(|v| v.get(99));
+///     assert!(missing.is_err(), "there is no index 99");
+/// }
+///
+/// {
+///     println!("* The write-side map behaves the same way.");
+///     let numbers = MainLock::new(|_| vec![1, 2, 3]);
+///
+///     let guard = unwrap(numbers.write());
+///     let mut mapped = guard.map(|v| &mut v[1]);
+///     *mapped = 42;
+///     assert_eq!(*mapped, 42);
+/// }
 /// ```
 pub struct MainLock<T> {
     lock: RwLock<T>,
@@ -139,6 +330,28 @@ impl<'a, T> WriteGuard<'a, T> where T: 'a {
             liveness: liveness.clone(),
         }
     }
+
+    /// Transform this guard into a guard for a sub-field of `T`, keeping the `MainLock` locked
+    /// for as long as the resulting guard is alive.
+    pub fn map<U, F>(self, f: F) -> MappedWriteGuard<'a, T, U>
+        where F: FnOnce(&mut T) -> &mut U
+    {
+        let mut guard = self;
+        let ptr: *mut U = f(&mut *guard);
+        MappedWriteGuard { _guard: guard, ptr: ptr }
+    }
+
+    /// As `map`, but the projection may fail, in which case the original guard is returned.
+    pub fn filter_map<U, F>(self, f: F) -> Result<MappedWriteGuard<'a, T, U>, Self>
+        where F: FnOnce(&mut T) -> Option<&mut U>
+    {
+        let mut guard = self;
+        let ptr: *mut U = match f(&mut *guard) {
+            Some(reference) => reference,
+            None => return Err(guard),
+        };
+        Ok(MappedWriteGuard { _guard: guard, ptr: ptr })
+    }
 }
 
 impl<'a, T> Deref for WriteGuard<'a, T> {
@@ -160,6 +373,79 @@ impl<'a, T> Drop for WriteGuard<'a, T> {
     }
 }
 
+/// A read guard for a sub-field of the value protected by a `MainLock`, obtained through
+/// `ReadGuard::map`/`filter_map`. Keeps the original lock held for as long as it is alive.
+pub struct MappedReadGuard<'a, T, U> where T: 'a, U: 'a {
+    _guard: ReadGuard<'a, T>,
+    ptr: *const U,
+}
+
+impl<'a, T, U> Deref for MappedReadGuard<'a, T, U> {
+    type Target = U;
+    fn deref(&self) -> &U {
+        unsafe { &*self.ptr }
+    }
+}
+
+/// The raw `ptr` field blocks the auto-derived `Sync`, even though the underlying `ReadGuard`
+/// is `Sync` whenever `T: Sync`. Restore it explicitly, as `parking_lot`/`lock_api` do for their
+/// own mapped guards.
+unsafe impl<'a, T, U> Sync for MappedReadGuard<'a, T, U> where T: Sync, U: Sync {
+}
+
+/// A write guard for a sub-field of the value protected by a `MainLock`, obtained through
+/// `WriteGuard::map`/`filter_map`. Keeps the original lock held for as long as it is alive.
+pub struct MappedWriteGuard<'a, T, U> where T: 'a, U: 'a {
+    _guard: WriteGuard<'a, T>,
+    ptr: *mut U,
+}
+
+impl<'a, T, U> Deref for MappedWriteGuard<'a, T, U> {
+    type Target = U;
+    fn deref(&self) -> &U {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<'a, T, U> DerefMut for MappedWriteGuard<'a, T, U> {
+    fn deref_mut(&mut self) -> &mut U {
+        unsafe { &mut *self.ptr }
+    }
+}
+
+/// The raw `ptr` field blocks the auto-derived `Sync`, even though the underlying `WriteGuard`
+/// is `Sync` whenever `T: Sync`. Restore it explicitly, as `parking_lot`/`lock_api` do for their
+/// own mapped guards.
+unsafe impl<'a, T, U> Sync for MappedWriteGuard<'a, T, U> where T: Sync, U: Sync {
+}
+
+/// Extends `ReadGuard` (a plain `RwLockReadGuard`) with the ability to project to a sub-field,
+/// mirroring `WriteGuard::map`/`filter_map`.
+pub trait ReadGuardMap<'a, T> where T: 'a {
+    fn map<U, F>(self, f: F) -> MappedReadGuard<'a, T, U> where F: FnOnce(&T) -> &U;
+    fn filter_map<U, F>(self, f: F) -> Result<MappedReadGuard<'a, T, U>, Self>
+        where F: FnOnce(&T) -> Option<&U>, Self: Sized;
+}
+
+impl<'a, T> ReadGuardMap<'a, T> for ReadGuard<'a, T> where T: 'a {
+    fn map<U, F>(self, f: F) -> MappedReadGuard<'a, T, U>
+        where F: FnOnce(&T) -> &U
+    {
+        let ptr: *const U = f(&*self);
+        MappedReadGuard { _guard: self, ptr: ptr }
+    }
+
+    fn filter_map<U, F>(self, f: F) -> Result<MappedReadGuard<'a, T, U>, Self>
+        where F: FnOnce(&T) -> Option<&U>
+    {
+        let ptr: *const U = match f(&*self) {
+            Some(reference) => reference,
+            None => return Err(self),
+        };
+        Ok(MappedReadGuard { _guard: self, ptr: ptr })
+    }
+}
+
 impl<T> MainLock<T> {
     pub fn new<F>(cb: F) -> Self
         where F: FnOnce(&Arc<Liveness>) -> T
@@ -175,6 +461,13 @@ impl<T> MainLock<T> {
         }
     }
 
+    pub fn liveness(&self) -> &Arc<Liveness> {
+        &self.liveness
+    }
+}
+
+#[cfg(not(feature = "parking_lot"))]
+impl<T> MainLock<T> {
     pub fn read(&self) -> LockResult<ReadGuard<T>> {
         self.lock.read()
     }
@@ -202,9 +495,64 @@ impl<T> MainLock<T> {
             )))
         }
     }
+}
 
-    pub fn liveness(&self) -> &Arc<Liveness> {
-        &self.liveness
+/// `parking_lot` locks never poison, so the `read`/`write` family returns guards directly
+/// instead of wrapping them in `LockResult`/`TryLockResult`.
+#[cfg(feature = "parking_lot")]
+impl<T> MainLock<T> {
+    pub fn read(&self) -> ReadGuard<T> {
+        self.lock.read()
+    }
+
+    pub fn try_read(&self) -> Option<ReadGuard<T>> {
+        self.lock.try_read()
+    }
+
+    pub fn write(&self) -> WriteGuard<T> {
+        WriteGuard::new(self.lock.write(), &self.liveness)
+    }
+
+    pub fn try_write(&self) -> Option<WriteGuard<T>> {
+        self.lock.try_write().map(|guard| WriteGuard::new(guard, &self.liveness))
+    }
+
+    /// Acquire a read-level lock that can later be upgraded, in place, to a write-level lock
+    /// without dropping and re-locking. Only available with the `parking_lot` backend.
+    pub fn upgradable_read(&self) -> UpgradableReadGuard<T> {
+        UpgradableReadGuard::new(self.lock.upgradable_read(), &self.liveness)
+    }
+}
+
+/// A read-level guard that can be upgraded in place to a write-level `WriteGuard`, obtained
+/// through `MainLock::upgradable_read`. Only available with the `parking_lot` backend.
+#[cfg(feature = "parking_lot")]
+pub struct UpgradableReadGuard<'a, T> where T: 'a {
+    guard: RwLockUpgradableReadGuard<'a, T>,
+    liveness: Arc<Liveness>,
+}
+
+#[cfg(feature = "parking_lot")]
+impl<'a, T> UpgradableReadGuard<'a, T> where T: 'a {
+    fn new(guard: RwLockUpgradableReadGuard<'a, T>, liveness: &Arc<Liveness>) -> Self {
+        UpgradableReadGuard {
+            guard: guard,
+            liveness: liveness.clone(),
+        }
+    }
+
+    /// Upgrade this guard to a write-level `WriteGuard`, in place.
+    pub fn upgrade(self) -> WriteGuard<'a, T> {
+        let guard = RwLockUpgradableReadGuard::upgrade(self.guard);
+        WriteGuard::new(guard, &self.liveness)
+    }
+}
+
+#[cfg(feature = "parking_lot")]
+impl<'a, T> Deref for UpgradableReadGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &*self.guard
     }
 }
 
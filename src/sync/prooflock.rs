@@ -3,18 +3,15 @@
 
 use std::cell::{ UnsafeCell };
 use std::marker::PhantomData;
-use std::sync::{ LockResult, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockResult };
+use std::ops::{ Deref, DerefMut };
 
-/// A trait specifying that a structure supports immutable borrowing if some proof is provided.
-pub trait ProofBorrow<P, T> {
-    fn borrow<'a>(&'a self, proof: &P) -> &'a T;
-}
+#[cfg(not(feature = "parking_lot"))]
+use std::sync::{ LockResult, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockResult };
 
+#[cfg(feature = "parking_lot")]
+use parking_lot::{ RwLock, RwLockReadGuard, RwLockUpgradableReadGuard, RwLockWriteGuard };
 
-/// A trait specifying that a structure supports mutable borrowing if some proof is provided.
-pub trait ProofBorrowMut<P, T> {
-    fn borrow_mut<'a>(&'a self, proof: &P) -> &'a mut T;
-}
+pub use crate::proof::{ ProofBorrow, ProofBorrowMut };
 
 pub struct SubCell<T> {
     cell: UnsafeCell<T>,
@@ -30,6 +27,30 @@ impl<T> SubCell<T> {
             owner_key: proof.0,
         }
     }
+
+    /// Replace the value, without materializing a `&mut T`, as `Cell::set`.
+    pub fn set<'a>(&self, proof: &ProofMut<'a>, value: T) {
+        assert_eq!(self.owner_key, proof.0);
+        unsafe { *self.cell.get() = value; }
+    }
+
+    /// Return a copy of the current value, as `Cell::get`.
+    pub fn get<'a>(&self, proof: &ProofMut<'a>) -> T where T: Copy {
+        assert_eq!(self.owner_key, proof.0);
+        unsafe { *self.cell.get() }
+    }
+
+    /// Replace the value and return the previous one, as `Cell::replace`.
+    pub fn replace<'a>(&self, proof: &ProofMut<'a>, value: T) -> T {
+        use std::mem;
+        assert_eq!(self.owner_key, proof.0);
+        unsafe { mem::replace(&mut *self.cell.get(), value) }
+    }
+
+    /// Replace the value with its `Default` and return the previous one, as `Cell::take`.
+    pub fn take<'a>(&self, proof: &ProofMut<'a>) -> T where T: Default {
+        self.replace(proof, T::default())
+    }
 }
 
 impl<'b, T> ProofBorrow<Proof<'b>, T> for SubCell<T> {
@@ -70,6 +91,108 @@ pub struct ProofMut<'a>(usize, PhantomData<&'a()>);
 pub type ReadGuard<'a, T> = (Proof<'a>, RwLockReadGuard<'a, T>);
 pub type WriteGuard<'a, T> = (ProofMut<'a>, RwLockWriteGuard<'a, T>);
 
+/// A read guard for a sub-field of the value protected by a `MainLock`, obtained through
+/// `ReadGuardMap::map`/`filter_map`. Keeps the proof and the original lock alive.
+pub struct MappedReadGuard<'a, T, U> where T: 'a, U: 'a {
+    _guard: ReadGuard<'a, T>,
+    ptr: *const U,
+}
+
+impl<'a, T, U> Deref for MappedReadGuard<'a, T, U> {
+    type Target = U;
+    fn deref(&self) -> &U {
+        unsafe { &*self.ptr }
+    }
+}
+
+/// The raw `ptr` field blocks the auto-derived `Sync`, even though the underlying `ReadGuard`
+/// is `Sync` whenever `T: Sync`. Restore it explicitly, as `parking_lot`/`lock_api` do for their
+/// own mapped guards.
+unsafe impl<'a, T, U> Sync for MappedReadGuard<'a, T, U> where T: Sync, U: Sync {
+}
+
+/// A write guard for a sub-field of the value protected by a `MainLock`, obtained through
+/// `WriteGuardMap::map`/`filter_map`. Keeps the proof and the original lock alive.
+pub struct MappedWriteGuard<'a, T, U> where T: 'a, U: 'a {
+    _guard: WriteGuard<'a, T>,
+    ptr: *mut U,
+}
+
+impl<'a, T, U> Deref for MappedWriteGuard<'a, T, U> {
+    type Target = U;
+    fn deref(&self) -> &U {
+        unsafe { &*self.ptr }
+    }
+}
+
+/// The raw `ptr` field blocks the auto-derived `Sync`, even though the underlying `WriteGuard`
+/// is `Sync` whenever `T: Sync`. Restore it explicitly, as `parking_lot`/`lock_api` do for their
+/// own mapped guards.
+unsafe impl<'a, T, U> Sync for MappedWriteGuard<'a, T, U> where T: Sync, U: Sync {
+}
+
+impl<'a, T, U> DerefMut for MappedWriteGuard<'a, T, U> {
+    fn deref_mut(&mut self) -> &mut U {
+        unsafe { &mut *self.ptr }
+    }
+}
+
+/// Projects a `ReadGuard` down to a sub-field, keeping the main lock held for as long as the
+/// resulting `MappedReadGuard` is alive.
+pub trait ReadGuardMap<'a, T> where T: 'a {
+    fn map<U, F>(self, f: F) -> MappedReadGuard<'a, T, U> where F: FnOnce(&T) -> &U;
+    fn filter_map<U, F>(self, f: F) -> Result<MappedReadGuard<'a, T, U>, Self>
+        where F: FnOnce(&T) -> Option<&U>, Self: Sized;
+}
+
+impl<'a, T> ReadGuardMap<'a, T> for ReadGuard<'a, T> where T: 'a {
+    fn map<U, F>(self, f: F) -> MappedReadGuard<'a, T, U>
+        where F: FnOnce(&T) -> &U
+    {
+        let ptr: *const U = f(&self.1);
+        MappedReadGuard { _guard: self, ptr: ptr }
+    }
+
+    fn filter_map<U, F>(self, f: F) -> Result<MappedReadGuard<'a, T, U>, Self>
+        where F: FnOnce(&T) -> Option<&U>
+    {
+        let ptr: *const U = match f(&self.1) {
+            Some(reference) => reference,
+            None => return Err(self),
+        };
+        Ok(MappedReadGuard { _guard: self, ptr: ptr })
+    }
+}
+
+/// Projects a `WriteGuard` down to a sub-field, keeping the main lock held for as long as the
+/// resulting `MappedWriteGuard` is alive.
+pub trait WriteGuardMap<'a, T> where T: 'a {
+    fn map<U, F>(self, f: F) -> MappedWriteGuard<'a, T, U> where F: FnOnce(&mut T) -> &mut U;
+    fn filter_map<U, F>(self, f: F) -> Result<MappedWriteGuard<'a, T, U>, Self>
+        where F: FnOnce(&mut T) -> Option<&mut U>, Self: Sized;
+}
+
+impl<'a, T> WriteGuardMap<'a, T> for WriteGuard<'a, T> where T: 'a {
+    fn map<U, F>(self, f: F) -> MappedWriteGuard<'a, T, U>
+        where F: FnOnce(&mut T) -> &mut U
+    {
+        let mut guard = self;
+        let ptr: *mut U = f(&mut guard.1);
+        MappedWriteGuard { _guard: guard, ptr: ptr }
+    }
+
+    fn filter_map<U, F>(self, f: F) -> Result<MappedWriteGuard<'a, T, U>, Self>
+        where F: FnOnce(&mut T) -> Option<&mut U>
+    {
+        let mut guard = self;
+        let ptr: *mut U = match f(&mut guard.1) {
+            Some(reference) => reference,
+            None => return Err(guard),
+        };
+        Ok(MappedWriteGuard { _guard: guard, ptr: ptr })
+    }
+}
+
 /// A variant of `RwLock` with sublocks that can be opened at no cost by providing a proof
 /// that the main lock is opened.
 ///
@@ -78,24 +201,34 @@ pub type WriteGuard<'a, T> = (ProofMut<'a>, RwLockWriteGuard<'a, T>);
 /// use std::collections::HashMap;
 ///
 /// type State = HashMap<usize, SubCell<usize>>;
+///
+/// // `read()`/`write()` return `LockResult`/`TryLockResult` with the default (`std`) backend,
+/// // since a `RwLock` can be poisoned, but plain proof/guard pairs with `parking_lot` (which
+/// // never poisons). This local helper keeps the rest of the example the same under both
+/// // features.
+/// #[cfg(not(feature = "parking_lot"))]
+/// fn unwrap<T, E: std::fmt::Debug>(result: Result<T, E>) -> T { result.unwrap() }
+/// #[cfg(feature = "parking_lot")]
+/// fn unwrap<T>(value: T) -> T { value }
+///
 /// let data : MainLock<State> = MainLock::new(HashMap::new());
 ///
 /// {
 ///     println!("* Attempt to read in the MainLock.");
-///     let (_, guard) = data.read().unwrap();
+///     let (_, guard) = unwrap(data.read());
 ///     assert_eq!(guard.len(), 0);
 /// }
 ///
 /// {
 ///     println!("* Attempt to write in the MainLock.");
-///     let (proof, mut guard) = data.write().unwrap();
+///     let (proof, mut guard) = unwrap(data.write());
 ///     guard.insert(0, SubCell::new(&proof, 42));
 ///     assert_eq!(guard.len(), 1);
 /// }
 ///
 /// {
 ///     println!("* Attempt to read in a SubCell.");
-///     let (proof, guard) = data.read().unwrap();
+///     let (proof, guard) = unwrap(data.read());
 ///     assert_eq!(guard.len(), 1);
 ///     let cell = guard.get(&0).unwrap();
 ///     assert_eq!(*cell.borrow(&proof), 42);
@@ -103,7 +236,7 @@ pub type WriteGuard<'a, T> = (ProofMut<'a>, RwLockWriteGuard<'a, T>);
 ///
 /// {
 ///     println!("* Attempt to read and write in a SubCell.");
-///     let (proof, guard) = data.write().unwrap();
+///     let (proof, guard) = unwrap(data.write());
 ///     assert_eq!(guard.len(), 1);
 ///     let cell = guard.get(&0).unwrap();
 ///     assert_eq!(*cell.borrow(&proof), 42);
@@ -114,11 +247,64 @@ pub type WriteGuard<'a, T> = (ProofMut<'a>, RwLockWriteGuard<'a, T>);
 ///
 /// {
 ///     println!("* Check that the SubCell changes are kept.");
-///     let (proof, guard) = data.read().unwrap();
+///     let (proof, guard) = unwrap(data.read());
 ///     assert_eq!(guard.len(), 1);
 ///     let cell = guard.get(&0).unwrap();
 ///     assert_eq!(*cell.borrow(&proof), 99);
 /// }
+///
+/// {
+///     println!("* Cell-style get/set/replace/take, still gated by the proof.");
+///     let (proof, guard) = unwrap(data.write());
+///     let cell = guard.get(&0).unwrap();
+///
+///     assert_eq!(cell.get(&proof), 99);
+///     cell.set(&proof, 1);
+///     assert_eq!(cell.get(&proof), 1);
+///
+///     assert_eq!(cell.replace(&proof, 2), 1);
+///     assert_eq!(cell.get(&proof), 2);
+///
+///     assert_eq!(cell.take(&proof), 2);
+///     assert_eq!(cell.get(&proof), 0);
+/// }
+///
+/// {
+///     println!("* Project a guard down to a sub-field with map/filter_map.");
+///     let numbers: MainLock<Vec<usize>> = MainLock::new(vec![1, 2, 3]);
+///
+///     let guard = unwrap(numbers.read());
+///     let mapped = guard.map(|v| &v[1]);
+///     assert_eq!(*mapped, 2);
+/// }
+///
+/// {
+///     println!("* filter_map succeeds when the projection exists.");
+///     let numbers: MainLock<Vec<usize>> = MainLock::new(vec![1, 2, 3]);
+///
+///     let guard = unwrap(numbers.read());
+///     let found = guard.filter_map(|v| v.get(0)).ok();
+///     assert_eq!(*found.unwrap(), 1);
+/// }
+///
+/// {
+///     println!("* filter_map returns the original guard when the projection fails.");
+///     let numbers: MainLock<Vec<usize>> = MainLock::new(vec![1, 2, 3]);
+///
+///     let guard = unwrap(numbers.read());
+///     let missing = guard.filter_map(|v| v.get(99));
+///     assert!(missing.is_err(), "there is no index 99");
+/// }
+///
+/// {
+///     println!("* The write-side map behaves the same way.");
+///     let numbers: MainLock<Vec<usize>> = MainLock::new(vec![1, 2, 3]);
+///
+///     let guard = unwrap(numbers.write());
+///     let mut mapped = guard.map(|v| &mut v[1]);
+///     *mapped = 42;
+///     assert_eq!(*mapped, 42);
+/// }
 /// ```
 pub struct MainLock<T> {
     lock: RwLock<T>,
@@ -133,7 +319,10 @@ impl<T> MainLock<T> {
             ownership: ownership
         }
     }
+}
 
+#[cfg(not(feature = "parking_lot"))]
+impl<T> MainLock<T> {
     // As `RwLock.read`.
     pub fn read(&self) -> LockResult<ReadGuard<T>> {
         let proof = Proof(self.ownership, PhantomData);
@@ -175,3 +364,89 @@ impl<T> MainLock<T> {
     }
 }
 
+/// `parking_lot` locks never poison, so the `read`/`write` family returns proof/guard pairs
+/// directly instead of wrapping them in `LockResult`/`TryLockResult`.
+#[cfg(feature = "parking_lot")]
+impl<T> MainLock<T> {
+    // As `RwLock.read`.
+    pub fn read(&self) -> ReadGuard<T> {
+        let proof = Proof(self.ownership, PhantomData);
+        (proof, self.lock.read())
+    }
+
+    // As `RwLock.try_read`.
+    pub fn try_read(&self) -> Option<ReadGuard<T>> {
+        let proof = Proof(self.ownership, PhantomData);
+        self.lock.try_read().map(|ok| (proof, ok))
+    }
+
+    // As `RwLock.write`.
+    pub fn write(&self) -> WriteGuard<T> {
+        let proof = ProofMut(self.ownership, PhantomData);
+        (proof, self.lock.write())
+    }
+
+    // As `RwLock.try_write`.
+    pub fn try_write(&self) -> Option<WriteGuard<T>> {
+        let proof = ProofMut(self.ownership, PhantomData);
+        self.lock.try_write().map(|ok| (proof, ok))
+    }
+
+    /// Acquire a read-level proof that can later be upgraded, in place, to a write-level proof
+    /// without dropping and re-locking. Only available with the `parking_lot` backend.
+    pub fn upgradable_read(&self) -> (Proof, RwLockUpgradableReadGuard<T>) {
+        let proof = Proof(self.ownership, PhantomData);
+        (proof, self.lock.upgradable_read())
+    }
+
+    /// Upgrade a read-level proof obtained from `upgradable_read` to a write-level `WriteGuard`,
+    /// in place. Takes the read-level `Proof` by value (not just the guard) and drops it before
+    /// handing out the new `ProofMut`, so the borrow checker rejects any attempt to keep using
+    /// the stale read-level proof (e.g. via `SubCell::borrow`) once the lock has been upgraded.
+    ///
+    /// ```
+    /// use sublock::sync::prooflock::*;
+    /// use std::collections::HashMap;
+    ///
+    /// type State = HashMap<usize, SubCell<usize>>;
+    /// let data: MainLock<State> = MainLock::new(HashMap::new());
+    /// {
+    ///     let (proof, mut guard) = data.write();
+    ///     guard.insert(0, SubCell::new(&proof, 1));
+    /// }
+    ///
+    /// let (proof, guard) = data.upgradable_read();
+    /// assert_eq!(*guard.get(&0).unwrap().borrow(&proof), 1);
+    ///
+    /// let (proof, guard) = data.upgrade(proof, guard);
+    /// guard.get(&0).unwrap().set(&proof, 2);
+    /// assert_eq!(*guard.get(&0).unwrap().borrow(&proof), 2);
+    /// ```
+    ///
+    /// The read-level proof is consumed by `upgrade`, so it cannot be used afterwards to read
+    /// through a `SubCell` while a `ProofMut`-backed write is also live:
+    ///
+    /// ```compile_fail
+    /// use sublock::sync::prooflock::*;
+    /// use std::collections::HashMap;
+    ///
+    /// type State = HashMap<usize, SubCell<usize>>;
+    /// let data: MainLock<State> = MainLock::new(HashMap::new());
+    /// {
+    ///     let (proof, mut guard) = data.write();
+    ///     guard.insert(0, SubCell::new(&proof, 1));
+    /// }
+    ///
+    /// let (proof, guard) = data.upgradable_read();
+    ///
+    /// let (_, guard) = data.upgrade(proof, guard);
+    /// // `proof` was moved into `upgrade`, so this no longer compiles:
+    /// assert_eq!(*guard.get(&0).unwrap().borrow(&proof), 1);
+    /// ```
+    pub fn upgrade<'a>(&self, proof: Proof<'a>, guard: RwLockUpgradableReadGuard<'a, T>) -> WriteGuard<'a, T> {
+        drop(proof);
+        let proof = ProofMut(self.ownership, PhantomData);
+        (proof, RwLockUpgradableReadGuard::upgrade(guard))
+    }
+}
+